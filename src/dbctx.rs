@@ -0,0 +1,125 @@
+use chrono::{SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+
+/// Thin wrapper around a local SQLite connection recording every label
+/// mutation the bot makes, so operators have a queryable history of what
+/// changed and when. The bot only writes to this log; a run never reads it
+/// back, so it plays no part in deciding which issues to update.
+pub struct DbCtx {
+    conn: Connection,
+}
+
+impl DbCtx {
+    /// Open (or create) the state database at `path` and run any pending
+    /// migrations.
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        let db_ctx = Self { conn };
+        db_ctx.migrate()?;
+        Ok(db_ctx)
+    }
+
+    fn migrate(&self) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS label_changes (
+                id                INTEGER PRIMARY KEY AUTOINCREMENT,
+                issue_number      INTEGER NOT NULL,
+                distro            TEXT NOT NULL,
+                added             INTEGER NOT NULL,
+                sync_status_hash  TEXT NOT NULL,
+                recorded_at       TEXT NOT NULL
+            )",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS label_changes_issue_distro
+                ON label_changes (issue_number, distro)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Record that `SYNC_HOLD_LABEL` was added to (or removed from) an
+    /// issue for a given distro, triggered by a sync-status with the
+    /// given hash.
+    pub fn record_label_change(
+        &self,
+        issue_number: u64,
+        distro: &str,
+        added: bool,
+        sync_status_hash: &str,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO label_changes (issue_number, distro, added, sync_status_hash, recorded_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                issue_number as i64,
+                distro,
+                added,
+                sync_status_hash,
+                Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(added, sync_status_hash)` rows recorded for an issue/distro pair,
+    /// most recent first.
+    fn history(db_ctx: &DbCtx, issue_number: u64, distro: &str) -> Vec<(bool, String)> {
+        let mut stmt = db_ctx
+            .conn
+            .prepare(
+                "SELECT added, sync_status_hash FROM label_changes
+                    WHERE issue_number = ?1 AND distro = ?2
+                    ORDER BY id DESC",
+            )
+            .expect("preparing query failed");
+        stmt.query_map(params![issue_number as i64, distro], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .expect("querying history failed")
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .expect("reading history rows failed")
+    }
+
+    #[test]
+    fn records_label_changes_in_order() {
+        let db_ctx = DbCtx::open(":memory:").expect("opening in-memory db failed");
+
+        assert_eq!(history(&db_ctx, 42, "humble"), Vec::new());
+
+        db_ctx
+            .record_label_change(42, "humble", true, "hash-a")
+            .expect("recording label change failed");
+        db_ctx
+            .record_label_change(42, "humble", false, "hash-b")
+            .expect("recording label change failed");
+
+        assert_eq!(
+            history(&db_ctx, 42, "humble"),
+            vec![(false, "hash-b".to_string()), (true, "hash-a".to_string())]
+        );
+    }
+
+    #[test]
+    fn history_is_scoped_to_issue_and_distro() {
+        let db_ctx = DbCtx::open(":memory:").expect("opening in-memory db failed");
+
+        db_ctx
+            .record_label_change(1, "humble", true, "hash-a")
+            .expect("recording label change failed");
+        db_ctx
+            .record_label_change(2, "humble", true, "hash-a")
+            .expect("recording label change failed");
+        db_ctx
+            .record_label_change(1, "jazzy", true, "hash-a")
+            .expect("recording label change failed");
+
+        assert_eq!(history(&db_ctx, 1, "humble").len(), 1);
+    }
+}
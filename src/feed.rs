@@ -0,0 +1,147 @@
+use std::fs;
+
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Where to persist and render the sync-hold transition feed.
+#[derive(Debug, Clone)]
+pub struct FeedConfig {
+    /// JSON file holding every transition ever recorded, used as the
+    /// source of truth when re-rendering the feed.
+    pub events_path: String,
+    /// Atom XML document rendered from `events_path`; re-rendered only by a
+    /// run that records at least one new transition.
+    pub output_path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LabelTransitionDirection {
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelTransitionEvent {
+    pub issue_number: u64,
+    pub issue_title: String,
+    pub distro: String,
+    pub direction: LabelTransitionDirection,
+    /// RFC 3339 timestamp of when the transition was recorded.
+    pub timestamp: String,
+}
+
+impl LabelTransitionEvent {
+    pub fn new(
+        issue_number: u64,
+        issue_title: String,
+        distro: String,
+        direction: LabelTransitionDirection,
+    ) -> Self {
+        Self {
+            issue_number,
+            issue_title,
+            distro,
+            direction,
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    fn id(&self) -> String {
+        let direction = match self.direction {
+            LabelTransitionDirection::Added => "added",
+            LabelTransitionDirection::Removed => "removed",
+        };
+        format!(
+            "urn:rosdistro-sync-bot:issue-{}-{}-{}",
+            self.issue_number, direction, self.timestamp
+        )
+    }
+
+    fn title(&self) -> String {
+        let verb = match self.direction {
+            LabelTransitionDirection::Added => "entered",
+            LabelTransitionDirection::Removed => "left",
+        };
+        format!("{} {} sync hold", self.distro, verb)
+    }
+}
+
+/// Load every previously-recorded transition, or an empty list if the
+/// store doesn't exist yet.
+pub fn load_events(path: &str) -> Vec<LabelTransitionEvent> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_events(path: &str, events: &[LabelTransitionEvent]) {
+    let contents = serde_json::to_string_pretty(events).expect("serializing feed events failed");
+    fs::write(path, contents).expect("writing feed events failed");
+}
+
+/// Render the full transition history to an Atom feed document.
+pub fn render_atom_feed(events: &[LabelTransitionEvent], output_path: &str) {
+    let updated = events
+        .last()
+        .map(|event| event.timestamp.clone())
+        .unwrap_or_else(|| Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str("  <title>rosdistro sync-hold transitions</title>\n");
+    xml.push_str("  <id>urn:rosdistro-sync-bot:sync-hold-transitions</id>\n");
+    xml.push_str(&format!("  <updated>{updated}</updated>\n"));
+
+    for event in events {
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <id>{}</id>\n", escape_xml(&event.id())));
+        xml.push_str(&format!(
+            "    <title>{}</title>\n",
+            escape_xml(&event.title())
+        ));
+        xml.push_str(&format!("    <updated>{}</updated>\n", event.timestamp));
+        xml.push_str(&format!(
+            "    <summary>{}</summary>\n",
+            escape_xml(&format!(
+                "Issue #{}: {}",
+                event.issue_number, event.issue_title
+            ))
+        ));
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    fs::write(output_path, xml).expect("writing atom feed failed");
+}
+
+fn escape_xml(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_all_xml_special_characters() {
+        assert_eq!(
+            escape_xml(r#"<a>&'"b'"#),
+            "&lt;a&gt;&amp;&apos;&quot;b&apos;"
+        );
+    }
+
+    #[test]
+    fn leaves_plain_text_untouched() {
+        assert_eq!(
+            escape_xml("rolling entered sync hold"),
+            "rolling entered sync hold"
+        );
+    }
+}
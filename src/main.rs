@@ -1,14 +1,29 @@
+mod dbctx;
+mod feed;
+mod webhook;
+
+use dbctx::DbCtx;
+
 use dotenv::dotenv;
-use std::{collections::HashMap, env};
-use tokio;
+use std::{collections::HashMap, env, sync::Arc};
 
+use futures::future::join_all;
+use octocrab::service::middleware::retry::{NoOpRateLimitMetrics, RetryConfig};
 use octocrab::{self, models, params};
+use tokio::sync::{Mutex, Semaphore};
 
 use log::{debug, info};
-use reqwest;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 const SYNC_HOLD_LABEL: &str = "in_sync_hold";
+const DEFAULT_MAX_CONCURRENT_UPDATES: usize = 8;
+/// Bound on how many times octocrab's own rate-limit middleware will
+/// retry a request that came back 403/429 with rate-limit headers present,
+/// before giving up and surfacing the error. Keeps a permanently-forbidden
+/// request (bad token scope, SSO required, archived repo) failing fast
+/// instead of retrying forever.
+const MAX_RATE_LIMIT_RETRIES: usize = 5;
 
 #[derive(Debug, Deserialize, Serialize)]
 struct SyncStatus {
@@ -16,7 +31,7 @@ struct SyncStatus {
     in_sync_hold: bool,
 }
 
-type DistroToSyncStatus = HashMap<String, bool>;
+pub(crate) type DistroToSyncStatus = HashMap<String, bool>;
 
 fn sync_statuses_to_hashmap(sync_statuses: &Vec<SyncStatus>) -> DistroToSyncStatus {
     let mut distro_map = HashMap::<String, bool>::new();
@@ -26,14 +41,65 @@ fn sync_statuses_to_hashmap(sync_statuses: &Vec<SyncStatus>) -> DistroToSyncStat
     distro_map
 }
 
-async fn run(
+/// Stable hash of a sync-status snapshot, used to tell the audit log which
+/// version of the YAML triggered a given label change.
+fn hash_sync_status(distro_to_sync_status: &DistroToSyncStatus) -> String {
+    let mut distros: Vec<_> = distro_to_sync_status.iter().collect();
+    distros.sort_by_key(|(distro, _)| *distro);
+
+    let mut hasher = Sha256::new();
+    for (distro, in_sync_hold) in distros {
+        hasher.update(distro.as_bytes());
+        hasher.update([*in_sync_hold as u8]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// What happened to a single issue during a `run`.
+enum SyncOutcome {
+    /// The label change was sent to GitHub.
+    Applied(feed::LabelTransitionEvent),
+    /// `dry_run` was set, so the change was only diffed, not applied.
+    DryRun { added: bool },
+}
+
+/// Everything that's shared across every issue in a `run`, as opposed to
+/// per-issue state, bundled up so `sync_issue` takes one context argument
+/// instead of a parameter per piece of shared state.
+struct SyncContext<'a> {
+    issue_handler: &'a octocrab::issues::IssueHandler<'a>,
+    distro_to_sync_status: &'a DistroToSyncStatus,
+    distros: &'a [&'a String],
+    db_ctx: Option<&'a Mutex<DbCtx>>,
+    sync_status_hash: &'a str,
+    dry_run: bool,
+}
+
+pub(crate) async fn run(
     repo_org: String,
     repo_name: String,
     personal_access_token: String,
     distro_to_sync_status: DistroToSyncStatus,
+    feed_config: Option<feed::FeedConfig>,
+    db_path: Option<String>,
+    dry_run: bool,
 ) {
+    let sync_status_hash = hash_sync_status(&distro_to_sync_status);
+    let db_ctx =
+        db_path.map(|path| Mutex::new(DbCtx::open(&path).expect("Opening state database failed")));
+    let max_concurrent_updates = env::var("MAX_CONCURRENT_UPDATES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_UPDATES);
+    let semaphore = Semaphore::new(max_concurrent_updates);
+
     let octocrab = octocrab::Octocrab::builder()
         .personal_token(personal_access_token)
+        .add_retry_config(RetryConfig::HandleRateLimits {
+            metrics: Arc::new(NoOpRateLimitMetrics),
+            max_retries: MAX_RATE_LIMIT_RETRIES,
+            min_wait_seconds: 1,
+        })
         .build()
         .expect("Creating octocrab instance failed");
     let issue_handler = octocrab.issues(repo_org, repo_name);
@@ -53,64 +119,153 @@ async fn run(
 
     let distros = distro_to_sync_status.keys().collect::<Vec<_>>();
 
-    for issue in issues {
-        let mut labels: Vec<_> = issue
-            .labels
-            .iter()
-            .map(|label| label.name.clone())
-            .collect();
-
-        let distro = distros
-            .iter()
-            .find(|distro| labels.contains(distro))
-            .expect("distro not found in labels");
-        let is_in_sync = *distro_to_sync_status
-            .get(&**distro)
-            .expect("distro not found in distro_map");
-        let is_labeled_as_in_sync_hold = labels.iter().any(|label| label == SYNC_HOLD_LABEL);
-
-        if is_in_sync == is_labeled_as_in_sync_hold {
-            debug!(
-                "Issue {} is labeled correctly {} the '{}' label",
-                issue.number,
-                if is_in_sync { "with" } else { "without" },
-                SYNC_HOLD_LABEL
-            );
-            continue; // labeled correctly do nothing
+    let ctx = SyncContext {
+        issue_handler: &issue_handler,
+        distro_to_sync_status: &distro_to_sync_status,
+        distros: &distros,
+        db_ctx: db_ctx.as_ref(),
+        sync_status_hash: &sync_status_hash,
+        dry_run,
+    };
+
+    let updates = issues.into_iter().map(|issue| {
+        let semaphore = &semaphore;
+        let ctx = &ctx;
+        async move {
+            let _permit = semaphore.acquire().await.expect("semaphore closed");
+            sync_issue(issue, ctx).await
         }
+    });
 
-        if is_in_sync && !is_labeled_as_in_sync_hold {
-            info!(
-                "Adding '{}' label to issue #{}: {}",
-                SYNC_HOLD_LABEL, issue.number, issue.title
-            );
-            labels.push(String::from(SYNC_HOLD_LABEL));
-        } else if !is_in_sync && is_labeled_as_in_sync_hold {
-            info!(
-                "Removing '{}' label from issue #{}: {}",
-                SYNC_HOLD_LABEL, issue.number, issue.title
-            );
-            labels.remove(
-                labels
-                    .iter()
-                    .position(|label| label == SYNC_HOLD_LABEL)
-                    .expect("SYNC_HOLD_LABEL not found in labels"),
-            );
-        } else {
-            unreachable!("This should never happen");
+    let mut transitions = Vec::new();
+    let mut dry_run_additions = 0;
+    let mut dry_run_removals = 0;
+    for outcome in join_all(updates).await.into_iter().flatten() {
+        match outcome {
+            SyncOutcome::Applied(event) => transitions.push(event),
+            SyncOutcome::DryRun { added: true } => dry_run_additions += 1,
+            SyncOutcome::DryRun { added: false } => dry_run_removals += 1,
         }
+    }
+    if dry_run_additions + dry_run_removals > 0 {
+        info!(
+            "Dry run: would add the '{}' label to {} issue(s) and remove it from {} issue(s)",
+            SYNC_HOLD_LABEL, dry_run_additions, dry_run_removals
+        );
+    }
+
+    if let Some(feed_config) = feed_config {
+        if !transitions.is_empty() {
+            let mut events = feed::load_events(&feed_config.events_path);
+            events.extend(transitions);
+            feed::save_events(&feed_config.events_path, &events);
+            feed::render_atom_feed(&events, &feed_config.output_path);
+        }
+    }
+}
+
+/// Bring a single issue's `SYNC_HOLD_LABEL` in line with its distro's sync
+/// status, recording the transition if one was made. Returns `None` when
+/// the issue was already labeled correctly, which is decided from the
+/// issue's live labels so a label that drifted out of band (e.g. someone
+/// manually edited it) still gets reconciled rather than being skipped
+/// because the audit log has already seen this sync-status hash.
+async fn sync_issue(issue: models::issues::Issue, ctx: &SyncContext<'_>) -> Option<SyncOutcome> {
+    let mut labels: Vec<_> = issue
+        .labels
+        .iter()
+        .map(|label| label.name.clone())
+        .collect();
+
+    let distro = ctx
+        .distros
+        .iter()
+        .find(|distro| labels.contains(distro))
+        .expect("distro not found in labels");
+    let is_in_sync = *ctx
+        .distro_to_sync_status
+        .get(&**distro)
+        .expect("distro not found in distro_map");
+    let is_labeled_as_in_sync_hold = labels.iter().any(|label| label == SYNC_HOLD_LABEL);
+
+    if is_in_sync == is_labeled_as_in_sync_hold {
+        debug!(
+            "Issue {} is labeled correctly {} the '{}' label",
+            issue.number,
+            if is_in_sync { "with" } else { "without" },
+            SYNC_HOLD_LABEL
+        );
+        return None; // labeled correctly do nothing
+    }
+
+    let old_labels = labels.clone();
+
+    let direction = if is_in_sync && !is_labeled_as_in_sync_hold {
+        info!(
+            "Adding '{}' label to issue #{}: {}",
+            SYNC_HOLD_LABEL, issue.number, issue.title
+        );
+        labels.push(String::from(SYNC_HOLD_LABEL));
+        feed::LabelTransitionDirection::Added
+    } else if !is_in_sync && is_labeled_as_in_sync_hold {
+        info!(
+            "Removing '{}' label from issue #{}: {}",
+            SYNC_HOLD_LABEL, issue.number, issue.title
+        );
+        labels.remove(
+            labels
+                .iter()
+                .position(|label| label == SYNC_HOLD_LABEL)
+                .expect("SYNC_HOLD_LABEL not found in labels"),
+        );
+        feed::LabelTransitionDirection::Removed
+    } else {
+        unreachable!("This should never happen");
+    };
+
+    if ctx.dry_run {
+        let old_labels_joined = old_labels.join("\n");
+        let new_labels_joined = labels.join("\n");
+        let patch = diffy::create_patch(&old_labels_joined, &new_labels_joined);
+        info!(
+            "Dry run: issue #{} ({}) labels would change:\n{}",
+            issue.number, issue.title, patch
+        );
+        return Some(SyncOutcome::DryRun {
+            added: direction == feed::LabelTransitionDirection::Added,
+        });
+    }
 
-        issue_handler
-            .update(issue.number)
-            .labels(&labels)
-            .send()
+    ctx.issue_handler
+        .update(issue.number)
+        .labels(&labels)
+        .send()
+        .await
+        .unwrap_or_else(|err| panic!("Updating issue #{} failed: {err}", issue.number));
+    debug!("Updated issue #{}: {:?}", issue.number, issue.title);
+
+    if let Some(db_ctx) = ctx.db_ctx {
+        db_ctx
+            .lock()
             .await
-            .expect("Updating issue failed");
-        debug!("Updated issue #{}: {:?}", issue.number, issue.title);
+            .record_label_change(
+                issue.number,
+                distro,
+                direction == feed::LabelTransitionDirection::Added,
+                ctx.sync_status_hash,
+            )
+            .expect("Recording label change failed");
     }
+
+    Some(SyncOutcome::Applied(feed::LabelTransitionEvent::new(
+        issue.number,
+        issue.title.clone(),
+        (**distro).clone(),
+        direction,
+    )))
 }
 
-async fn get_rosdisto_to_sync_status(url: String) -> DistroToSyncStatus {
+pub(crate) async fn get_rosdisto_to_sync_status(url: String) -> DistroToSyncStatus {
     let response = reqwest::get(&url)
         .await
         .expect("request to get sync status YAML failed");
@@ -137,6 +292,40 @@ async fn main() {
     let personal_access_token =
         env::var("GITHUB_PERSONAL_ACCESS_TOKEN").expect("GITHUB_PERSONAL_ACCESS_TOKEN not defined");
 
+    let feed_config = match (env::var("FEED_EVENTS_PATH"), env::var("FEED_OUTPUT_PATH")) {
+        (Ok(events_path), Ok(output_path)) => Some(feed::FeedConfig {
+            events_path,
+            output_path,
+        }),
+        _ => None,
+    };
+    let db_path = env::var("STATE_DB_PATH").ok();
+    let dry_run =
+        env::args().any(|arg| arg == "--dry-run") || env::var("DRY_RUN").as_deref() == Ok("1");
+
+    if env::var("MODE").as_deref() == Ok("webhook") {
+        let webhook_secret =
+            env::var("GITHUB_WEBHOOK_SECRET").expect("GITHUB_WEBHOOK_SECRET not defined");
+        let addr = env::var("WEBHOOK_LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".to_string());
+
+        webhook::serve(
+            webhook::WebhookState {
+                repo_org,
+                repo_name,
+                repo_branch_name,
+                repo_path_to_sync_status,
+                personal_access_token,
+                webhook_secret,
+                feed_config,
+                db_path,
+                dry_run,
+            },
+            &addr,
+        )
+        .await;
+        return;
+    }
+
     let url_to_file = format!("https://raw.githubusercontent.com/{repo_org}/{repo_name}/{repo_branch_name}/{repo_path_to_sync_status}");
     let distro_to_sync_status = get_rosdisto_to_sync_status(url_to_file).await;
     info!("distro_to_sync_status: {:?}", distro_to_sync_status);
@@ -146,6 +335,9 @@ async fn main() {
         repo_name,
         personal_access_token,
         distro_to_sync_status,
+        feed_config,
+        db_path,
+        dry_run,
     )
     .await;
 }
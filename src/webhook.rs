@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use hmac::{Hmac, KeyInit, Mac};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{feed, get_rosdisto_to_sync_status, run, DistroToSyncStatus};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Everything the webhook handler needs to react to a rosdistro push and
+/// re-run the sync, mirroring the arguments threaded through `run` in the
+/// one-shot mode.
+pub struct WebhookState {
+    pub repo_org: String,
+    pub repo_name: String,
+    pub repo_branch_name: String,
+    pub repo_path_to_sync_status: String,
+    pub personal_access_token: String,
+    pub webhook_secret: String,
+    pub feed_config: Option<feed::FeedConfig>,
+    pub db_path: Option<String>,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct PushEvent {
+    commits: Vec<PushCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PushCommit {
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    removed: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+}
+
+impl PushCommit {
+    fn touches(&self, path: &str) -> bool {
+        self.added.iter().any(|p| p == path)
+            || self.removed.iter().any(|p| p == path)
+            || self.modified.iter().any(|p| p == path)
+    }
+}
+
+/// Start the axum HTTP server and listen for GitHub `push` webhooks,
+/// re-syncing labels whenever the sync-status YAML changes.
+pub async fn serve(state: WebhookState, addr: &str) {
+    let state = Arc::new(state);
+    let app = Router::new()
+        .route("/webhook", post(handle_push))
+        .with_state(state);
+
+    info!("Listening for rosdistro push webhooks on {addr}");
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .expect("Binding webhook listener failed");
+    axum::serve(listener, app)
+        .await
+        .expect("Webhook server failed");
+}
+
+async fn handle_push(
+    State(state): State<Arc<WebhookState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let signature = match headers
+        .get("X-Hub-Signature-256")
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(signature) => signature,
+        None => {
+            warn!("Rejecting webhook request: missing X-Hub-Signature-256 header");
+            return StatusCode::UNAUTHORIZED;
+        }
+    };
+
+    if !verify_signature(state.webhook_secret.as_bytes(), &body, signature) {
+        warn!("Rejecting webhook request: signature verification failed");
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let event = headers
+        .get("X-GitHub-Event")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if event != "push" {
+        debug!("Ignoring '{event}' event, only 'push' triggers a sync");
+        return StatusCode::OK;
+    }
+
+    let push_event: PushEvent = match serde_json::from_slice(&body) {
+        Ok(push_event) => push_event,
+        Err(err) => {
+            warn!("Rejecting webhook request: invalid push event JSON: {err}");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    if !push_event
+        .commits
+        .iter()
+        .any(|commit| commit.touches(&state.repo_path_to_sync_status))
+    {
+        debug!(
+            "Push didn't touch {}, ignoring",
+            state.repo_path_to_sync_status
+        );
+        return StatusCode::OK;
+    }
+
+    info!(
+        "{} changed, re-syncing labels for {}/{}",
+        state.repo_path_to_sync_status, state.repo_org, state.repo_name
+    );
+
+    // Acknowledge the delivery immediately and run the sync in the
+    // background: GitHub considers a webhook delivery timed out (and will
+    // redeliver it) after ~10s, and a full sync over a large repo's issues
+    // can easily take longer than that. Awaiting it here would risk
+    // concurrent duplicate `run`s racing to open the same SQLite `DbCtx`.
+    tokio::spawn(async move {
+        let url_to_file = format!(
+            "https://raw.githubusercontent.com/{}/{}/{}/{}",
+            state.repo_org, state.repo_name, state.repo_branch_name, state.repo_path_to_sync_status
+        );
+        let distro_to_sync_status: DistroToSyncStatus =
+            get_rosdisto_to_sync_status(url_to_file).await;
+
+        run(
+            state.repo_org.clone(),
+            state.repo_name.clone(),
+            state.personal_access_token.clone(),
+            distro_to_sync_status,
+            state.feed_config.clone(),
+            state.db_path.clone(),
+            state.dry_run,
+        )
+        .await;
+    });
+
+    StatusCode::ACCEPTED
+}
+
+/// Verify a GitHub `sha256=<hex>` signature over the raw request body in
+/// constant time.
+fn verify_signature(secret: &[u8], body: &[u8], signature_header: &str) -> bool {
+    let Some(hex_signature) = signature_header.strip_prefix("sha256=") else {
+        return false;
+    };
+    let Ok(signature_bytes) = hex::decode(hex_signature) else {
+        return false;
+    };
+
+    let Ok(mut mac) = HmacSha256::new_from_slice(secret) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature_bytes).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &[u8], body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        let secret = b"webhook-secret";
+        let body = b"{\"commits\":[]}";
+        let signature = sign(secret, body);
+
+        assert!(verify_signature(secret, body, &signature));
+    }
+
+    #[test]
+    fn rejects_a_signature_from_the_wrong_secret() {
+        let body = b"{\"commits\":[]}";
+        let signature = sign(b"wrong-secret", body);
+
+        assert!(!verify_signature(b"webhook-secret", body, &signature));
+    }
+
+    #[test]
+    fn rejects_a_signature_for_a_tampered_body() {
+        let secret = b"webhook-secret";
+        let signature = sign(secret, b"{\"commits\":[]}");
+
+        assert!(!verify_signature(secret, b"{\"commits\":[{}]}", &signature));
+    }
+
+    #[test]
+    fn rejects_a_malformed_signature_header() {
+        let secret = b"webhook-secret";
+        let body = b"{\"commits\":[]}";
+
+        assert!(!verify_signature(secret, body, "not-a-valid-signature"));
+        assert!(!verify_signature(secret, body, "sha256=not-hex"));
+    }
+}